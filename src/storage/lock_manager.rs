@@ -0,0 +1,207 @@
+// Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Coordinates waiters blocked on another transaction's lock: waking them once that lock
+//! clears, and detecting deadlocks among them before they'd otherwise just block forever.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use txn_types::TimeStamp;
+
+/// Resolves a blocked transaction's wait against the rest of the system.
+pub trait LockManager: Clone + Send + 'static {
+    /// Wakes transactions that were waiting on `lock_ts`'s lock (identified by `hashes`),
+    /// now that it has been resolved as described by `commit_ts`/`is_pessimistic_txn`.
+    fn wake_up(
+        &self,
+        lock_ts: TimeStamp,
+        hashes: Vec<u64>,
+        commit_ts: TimeStamp,
+        is_pessimistic_txn: bool,
+    ) {
+        let _ = (lock_ts, hashes, commit_ts, is_pessimistic_txn);
+    }
+
+    /// Registers that the transaction starting at `caller_start_ts` is blocked waiting on
+    /// `lock_ts`'s lock (hashed as `lock_hash`; `for_update_ts` is set for a pessimistic
+    /// lock), then walks up to `depth` hops of the wait-for graph looking for a cycle back
+    /// to `caller_start_ts`. Returns the start_ts that should be rolled back to break the
+    /// cycle, if one was found.
+    fn detect_deadlock(
+        &self,
+        caller_start_ts: TimeStamp,
+        lock_ts: TimeStamp,
+        lock_hash: u64,
+        for_update_ts: Option<TimeStamp>,
+        depth: u32,
+    ) -> Option<TimeStamp> {
+        let _ = (caller_start_ts, lock_ts, lock_hash, for_update_ts, depth);
+        None
+    }
+
+    /// Drops every wait-for edge pointing at `lock_ts`: it was just committed or rolled
+    /// back, so it can no longer block anyone, and there is no point waiting for its entry
+    /// in the graph to expire on its own.
+    fn clean_up_wait_for(&self, lock_ts: TimeStamp) {
+        let _ = lock_ts;
+    }
+}
+
+/// A [`LockManager`] that does nothing. Used by tests and by callers that never need
+/// waiter bookkeeping or deadlock detection at all (e.g. a single-node raw KV deployment).
+#[derive(Clone, Copy, Default)]
+pub struct DummyLockManager;
+
+impl LockManager for DummyLockManager {}
+
+/// How long an unresolved wait-for edge is trusted before it's treated as stale. This bounds,
+/// rather than closes, the window for a false-positive deadlock report: [`clean_up_wait_for`]
+/// is only actually called from the two `CheckTxnStatus` resolution paths, so a lock resolved
+/// any other way (an ordinary `Commit`, `Rollback`, or `ResolveLock`) leaves its edge live in
+/// the graph, and a waiter whose cycle happens to close through it can still be reported as
+/// deadlocked, until that edge's own deadline passes. `EDGE_TTL` is the cap on how long that
+/// window can stay open, not a guarantee that it never opens.
+///
+/// [`clean_up_wait_for`]: LockManager::clean_up_wait_for
+const EDGE_TTL: Duration = Duration::from_secs(3);
+
+struct Edge {
+    /// The transaction `waiter_ts` is blocked on.
+    blocked_on: TimeStamp,
+    deadline: Instant,
+}
+
+/// An in-process deadlock detector backed by a wait-for graph keyed by the blocked
+/// transaction's `start_ts`: `waiter_ts -> [transactions it's waiting on]`.
+///
+/// This is the single-node building block a distributed `LockManager` would shard
+/// transactions across and query over RPC; used standalone here (and in tests), it detects
+/// exactly the deadlocks whose whole cycle happens to live on this node.
+#[derive(Clone, Default)]
+pub struct WaitForGraph {
+    waits_for: Arc<Mutex<HashMap<TimeStamp, Vec<Edge>>>>,
+}
+
+impl WaitForGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn register(&self, waiter_ts: TimeStamp, blocked_on: TimeStamp) {
+        let mut waits_for = self.waits_for.lock().unwrap();
+        let deadline = Instant::now() + EDGE_TTL;
+        let edges = waits_for.entry(waiter_ts).or_default();
+        edges.retain(|e| e.blocked_on != blocked_on);
+        edges.push(Edge { blocked_on, deadline });
+    }
+
+    /// Depth-bounded DFS: does `start` transitively wait for `target`, following only
+    /// non-expired edges?
+    ///
+    /// Running out of `depth` aborts the search rather than reporting a deadlock, so a
+    /// large or long wait-for chain can only cause a *missed* detection (the caller just
+    /// retries later, same as it would if this detector weren't consulted at all), never a
+    /// false positive that rolls back a transaction that wasn't actually deadlocked.
+    fn has_path(
+        waits_for: &HashMap<TimeStamp, Vec<Edge>>,
+        start: TimeStamp,
+        target: TimeStamp,
+        depth: u32,
+    ) -> bool {
+        if depth == 0 {
+            return false;
+        }
+        let now = Instant::now();
+        let edges = match waits_for.get(&start) {
+            Some(edges) => edges,
+            None => return false,
+        };
+        edges.iter().any(|edge| {
+            edge.deadline >= now
+                && (edge.blocked_on == target
+                    || Self::has_path(waits_for, edge.blocked_on, target, depth - 1))
+        })
+    }
+}
+
+impl LockManager for WaitForGraph {
+    fn detect_deadlock(
+        &self,
+        caller_start_ts: TimeStamp,
+        lock_ts: TimeStamp,
+        _lock_hash: u64,
+        _for_update_ts: Option<TimeStamp>,
+        depth: u32,
+    ) -> Option<TimeStamp> {
+        self.register(caller_start_ts, lock_ts);
+
+        let waits_for = self.waits_for.lock().unwrap();
+        // `caller_start_ts` is now waiting on `lock_ts`. That's a deadlock exactly when
+        // `lock_ts` was already (transitively) waiting on `caller_start_ts`, i.e. there's a
+        // path back from `lock_ts` to `caller_start_ts` in the graph as it stood before this
+        // edge was added.
+        if Self::has_path(&waits_for, lock_ts, caller_start_ts, depth) {
+            Some(caller_start_ts)
+        } else {
+            None
+        }
+    }
+
+    fn clean_up_wait_for(&self, lock_ts: TimeStamp) {
+        let mut waits_for = self.waits_for.lock().unwrap();
+        waits_for.remove(&lock_ts);
+        for edges in waits_for.values_mut() {
+            edges.retain(|e| e.blocked_on != lock_ts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_two_txn_cycle() {
+        let g = WaitForGraph::new();
+        // T2 waits on T1's lock: no cycle yet.
+        assert_eq!(g.detect_deadlock(2.into(), 1.into(), 0, None, 32), None);
+        // T1 now waits on T2's lock too: that closes the cycle, so T1 (the transaction
+        // whose new wait just completed it) is reported as the victim.
+        assert_eq!(
+            g.detect_deadlock(1.into(), 2.into(), 0, None, 32),
+            Some(1.into())
+        );
+    }
+
+    #[test]
+    fn test_depth_bound_is_conservative() {
+        let g = WaitForGraph::new();
+        // Build a chain: 2 waits on 3, 3 waits on 4, ..., 9 waits on 10.
+        for i in 2u64..10 {
+            assert_eq!(g.detect_deadlock(i.into(), (i + 1).into(), 0, None, 32), None);
+        }
+        // 10 waiting on 2 closes a 9-hop cycle, whose path from 2 back to 10 is 8 hops. A
+        // depth of 3 can't walk that far and must miss it rather than report a false positive.
+        assert_eq!(
+            g.detect_deadlock(10.into(), 2.into(), 0, None, 3),
+            None
+        );
+        // Re-registering with enough depth finds the very same cycle.
+        assert_eq!(
+            g.detect_deadlock(10.into(), 2.into(), 0, None, 32),
+            Some(10.into())
+        );
+    }
+
+    #[test]
+    fn test_clean_up_wait_for_drops_edges() {
+        let g = WaitForGraph::new();
+        // T2 waits on T1.
+        assert_eq!(g.detect_deadlock(2.into(), 1.into(), 0, None, 32), None);
+        // T1's lock is resolved: the edge pointing at it must be forgotten.
+        g.clean_up_wait_for(1.into());
+        // T1 waiting on T2 no longer closes a cycle, since T2's wait on T1 is gone.
+        assert_eq!(g.detect_deadlock(1.into(), 2.into(), 0, None, 32), None);
+    }
+}
@@ -1,6 +1,7 @@
 // Copyright 2020 TiKV Project Authors. Licensed under Apache-2.0.
 
-use txn_types::{Key, TimeStamp};
+use kvproto::kvrpcpb::Assertion;
+use txn_types::{Key, Lock, TimeStamp, WriteType};
 
 use crate::storage::kv::WriteData;
 use crate::storage::lock_manager::LockManager;
@@ -8,12 +9,21 @@ use crate::storage::mvcc::metrics::MVCC_CHECK_TXN_STATUS_COUNTER_VEC;
 use crate::storage::mvcc::txn::MissingLockAction;
 use crate::storage::mvcc::MvccTxn;
 use crate::storage::txn::commands::{
-    Command, CommandExt, ReleasedLocks, TypedCommand, WriteCommand, WriteContext, WriteResult,
+    Command, CommandExt, ReleasedLock, ReleasedLocks, TypedCommand, WriteCommand, WriteContext,
+    WriteResult,
 };
 use crate::storage::txn::Result;
 use crate::storage::{ProcessResult, Snapshot, TxnStatus};
 use std::mem;
 
+/// The depth bound for the wait-for cycle search kicked off by `CheckTxnStatus`. It is a fixed
+/// constant: callers cannot change it per command or through configuration.
+///
+/// The search must fail conservatively: running out of depth aborts the search rather than
+/// reporting a deadlock, so a large transaction graph can never produce a false positive, only a
+/// missed (and later retried) detection.
+const DEADLOCK_DETECT_DEPTH: u32 = 32;
+
 command! {
     /// Check the status of a transaction. This is usually invoked by a transaction that meets
     /// another transaction's lock. If the primary lock is expired, it will rollback the primary
@@ -23,6 +33,30 @@ command! {
     /// This is invoked on a transaction's primary lock. The lock may be generated by either
     /// [`AcquirePessimisticLock`](Command::AcquirePessimisticLock) or
     /// [`Prewrite`](Command::Prewrite).
+    ///
+    /// When the lock is still valid, `caller_start_ts` is blocked on `lock_ts` and this registers
+    /// that wait-for edge with the [`LockManager`](LockManager)'s deadlock detector. If following
+    /// the edge finds a cycle back to `caller_start_ts` within `DEADLOCK_DETECT_DEPTH` hops, the
+    /// command reports a deadlock instead of pushing `min_commit_ts`.
+    ///
+    /// A transaction committed through the one-phase-commit fast path never goes through this
+    /// command's lock-expiry path at all: it never held a lock here in the first place, so this
+    /// command simply finds the committed `Write` record through the normal missing-lock lookup
+    /// and reports `Committed`, same as it would for a transaction that fell back to 2PC and left
+    /// a lock this command later resolved. Either way, observing a commit record here bumps
+    /// `max_ts` to its `commit_ts` (except in `read_only` mode), see the comment on that bump in
+    /// `resolve_primary_lock` for why this is done for any commit record found this way, not only
+    /// 1PC ones.
+    ///
+    /// This command does not implement the 1PC fast path or any part of its race closure: it
+    /// only consumes a 1PC commit's `Write` record once that record is already visible, the same
+    /// way it consumes any other commit found through the missing-lock lookup. The window between
+    /// a 1PC `Prewrite` proposing its write and that write becoming durable — during which a
+    /// concurrent caller here could see neither a lock nor a commit record and take the
+    /// missing-lock branch below — can only be closed on the `Prewrite` side, by giving it a
+    /// `try_one_pc` flag and an in-flight guard analogous to async commit's. `Prewrite` does not
+    /// exist in this source tree, so that guard is out of reach from this file; see the note on
+    /// the missing-lock branch in `resolve_primary_lock` for the specifics of what's missing.
     CheckTxnStatus:
         cmd_ty => TxnStatus,
         display => "kv::command::check_txn_status {} @ {} curr({}, {}) | {:?}", (primary_key, lock_ts, caller_start_ts, current_ts, ctx),
@@ -38,6 +72,57 @@ command! {
             /// Specifies the behavior when neither commit/rollback record nor lock is found. If true,
             /// rollbacks that transaction; otherwise returns an error.
             rollback_if_not_exist: bool,
+            /// Mirrors the skip-concurrency-control mode of pessimistic transactions: when true,
+            /// this command only observes the primary's status and never mutates it. `max_ts` is
+            /// not bumped, `min_commit_ts` is never pushed, and an expired lock is reported but not
+            /// rolled back. Useful for tooling and lock diagnostics that must not disturb the lock
+            /// they are inspecting.
+            read_only: bool,
+            /// Forces the primary lock to be treated as expired and rolled back regardless of its
+            /// remaining TTL, mirroring the `lock_timeout_`/`expiration_time_` override pessimistic
+            /// transactions already support. The resulting status is
+            /// [`TxnStatus::ForceRollback`](TxnStatus::ForceRollback) rather than
+            /// [`TxnStatus::TtlExpire`](TxnStatus::TtlExpire), so callers can tell a genuine TTL
+            /// expiry from a caller-requested one.
+            force_rollback: bool,
+            /// An additional grace period, in milliseconds, added on top of the lock's own TTL
+            /// before it is considered expired. Absorbs clock skew between nodes; applied
+            /// identically to optimistic and pessimistic (`for_update_ts != 0`) locks.
+            ttl_grace_ms: u64,
+            /// An optional expectation, borrowed from the prewrite mutation assertion levels,
+            /// about whether the primary key exists according to its latest write record (a
+            /// committed `Delete` or a `Rollback` counts as not existing). Checked only when no
+            /// lock is found; a mismatch returns
+            /// [`TxnStatus::AssertionFailed`](TxnStatus::AssertionFailed) instead of silently
+            /// writing a protected rollback, so lock-resolution callers learn about lost-lock /
+            /// consistency violations immediately rather than at commit time.
+            assertion: Assertion,
+            /// When an expired lock would normally be rolled back, setting this instead reports
+            /// [`TxnStatus::RollbackScheduled`](TxnStatus::RollbackScheduled) rather than the
+            /// usual [`TxnStatus::TtlExpire`](TxnStatus::TtlExpire)/
+            /// [`TxnStatus::ForceRollback`](TxnStatus::ForceRollback). The rollback itself still
+            /// happens right here, inline, exactly like the non-deferred case — the lock's
+            /// removal can't be decoupled from writing its Rollback record without leaving a
+            /// window where the still-alive owner commits straight through the marker. What
+            /// `RollbackScheduled` actually buys the caller is not having to treat this
+            /// resolution as its own responsibility (no retry/escalation), so that batching the
+            /// cost of resolving the same primary across many simultaneous callers can happen
+            /// one layer up, e.g. in a resolve-lock worker that coalesces callers before any of
+            /// them reaches this command.
+            defer_rollback: bool,
+            /// A caller-supplied floor for the lock's `min_commit_ts`: when the lock is pushed
+            /// forward at all, it is pushed to at least `max(caller_start_ts.next(), min_commit_ts)`
+            /// instead of just `caller_start_ts.next()`. Zero means no extra floor. Lets a caller
+            /// that already knows it needs to read at some later ts clear the lock out of its way
+            /// in one round-trip instead of pushing and re-checking repeatedly.
+            min_commit_ts: TimeStamp,
+            /// Opts out of pushing `min_commit_ts` forward at all. Unlike `read_only`, TTL
+            /// resolution still runs as usual (an expired lock is still rolled back), but a
+            /// live lock is never registered with the deadlock detector: a non-blocking reader
+            /// that only wants to observe `min_commit_ts` to pick its snapshot point was never
+            /// actually waiting on this lock, so it must not add a wait-for edge or be handed
+            /// back a `Deadlock` victim on behalf of a block that was never going to happen.
+            no_push: bool,
         }
 }
 
@@ -58,14 +143,16 @@ impl<S: Snapshot, L: LockManager> WriteCommand<S, L> for CheckTxnStatus {
     /// the `current_ts` is literally the timestamp when this function is invoked. It may not be
     /// accurate.
     fn process_write(mut self, snapshot: S, context: WriteContext<'_, L>) -> Result<WriteResult> {
-        let mut new_max_ts = self.lock_ts;
-        if !self.current_ts.is_max() && self.current_ts > new_max_ts {
-            new_max_ts = self.current_ts;
-        }
-        if !self.caller_start_ts.is_max() && self.caller_start_ts > new_max_ts {
-            new_max_ts = self.caller_start_ts;
+        if !self.read_only {
+            let mut new_max_ts = self.lock_ts;
+            if !self.current_ts.is_max() && self.current_ts > new_max_ts {
+                new_max_ts = self.current_ts;
+            }
+            if !self.caller_start_ts.is_max() && self.caller_start_ts > new_max_ts {
+                new_max_ts = self.caller_start_ts;
+            }
+            context.concurrency_manager.update_max_ts(new_max_ts);
         }
-        context.concurrency_manager.update_max_ts(new_max_ts);
 
         let mut txn = MvccTxn::new(
             snapshot,
@@ -85,88 +172,453 @@ impl<S: Snapshot, L: LockManager> WriteCommand<S, L> for CheckTxnStatus {
             .into()
         ));
 
-        let result = match txn.reader.load_lock(&self.primary_key)? {
-            Some(mut lock) if lock.ts == self.lock_ts => {
-                if lock.use_async_commit
-                    && (!self.caller_start_ts.is_zero() || !self.current_ts.is_zero())
-                {
-                    warn!(
-                        "check async commit txn status with non-zero caller_start_ts or current_ts";
-                        "caller_start_ts" => self.caller_start_ts,
-                        "current_ts" => self.current_ts
-                    );
-                    self.caller_start_ts = TimeStamp::zero();
-                    self.current_ts = TimeStamp::zero();
+        let params = CheckTxnStatusParams {
+            caller_start_ts: self.caller_start_ts,
+            current_ts: self.current_ts,
+            rollback_if_not_exist: self.rollback_if_not_exist,
+            read_only: self.read_only,
+            force_rollback: self.force_rollback,
+            ttl_grace_ms: self.ttl_grace_ms,
+            assertion: self.assertion,
+            defer_rollback: self.defer_rollback,
+            min_commit_ts: self.min_commit_ts,
+            no_push: self.no_push,
+            detect_deadlock: true,
+        };
+        let read_only = self.read_only;
+        let (txn_status, released) =
+            resolve_primary_lock(&mut txn, self.primary_key, self.lock_ts, &params, context.lock_mgr)?;
+
+        released_locks.push(released);
+        // The lock is released here only when `read_only` is false and `check_txn_status`
+        // returns `TtlExpire`, `ForceRollback`, or `RollbackScheduled` (which, like the other
+        // two, actually rolls the lock back inline — see the comment on `defer_rollback` in
+        // `resolve_primary_lock`); in `read_only` mode those same statuses are reported without
+        // ever touching the lock, so gate on `!read_only` instead of trusting that
+        // `released_locks` happens to be empty whenever it's reached.
+        if !read_only
+            && matches!(
+                txn_status,
+                TxnStatus::TtlExpire | TxnStatus::ForceRollback | TxnStatus::RollbackScheduled
+            )
+        {
+            released_locks.wake_up(context.lock_mgr);
+        }
+
+        context.statistics.add(&txn.take_statistics());
+        let pr = ProcessResult::TxnStatus { txn_status };
+        let write_data = WriteData::from_modifies(txn.into_modifies());
+        Ok(WriteResult {
+            ctx,
+            to_be_write: write_data,
+            rows: if read_only { 0 } else { 1 },
+            pr,
+            lock_info: None,
+            lock_guards: vec![],
+        })
+    }
+}
+
+/// Per-key knobs for resolving one primary lock, factored out of [`CheckTxnStatus`]'s fields so
+/// that [`resolve_primary_lock`] can be shared with [`CheckTxnStatusBatch`], which only exposes a
+/// subset of them and defaults the rest to their no-op value (see
+/// [`CheckTxnStatusBatch::process_write`]).
+struct CheckTxnStatusParams {
+    caller_start_ts: TimeStamp,
+    current_ts: TimeStamp,
+    rollback_if_not_exist: bool,
+    read_only: bool,
+    force_rollback: bool,
+    ttl_grace_ms: u64,
+    assertion: Assertion,
+    defer_rollback: bool,
+    min_commit_ts: TimeStamp,
+    no_push: bool,
+    /// Whether a live, unexpired lock should be checked against the lock manager's deadlock
+    /// detector at all. [`CheckTxnStatusBatch`] opts out, same as it opts out of assertion
+    /// checking, to keep its per-key cost down.
+    detect_deadlock: bool,
+}
+
+/// Resolves a single `(primary_key, lock_ts)` pair against `txn`'s snapshot: checks the primary
+/// lock's TTL, rolls it back if expired, pushes its `min_commit_ts` forward if not, or reports the
+/// commit/rollback record already left behind if there's no lock at all. This is the single
+/// piece of logic both [`CheckTxnStatus`] and [`CheckTxnStatusBatch`] run per primary, so a fix
+/// to one always reaches the other.
+fn resolve_primary_lock<S: Snapshot, L: LockManager>(
+    txn: &mut MvccTxn<S>,
+    primary_key: Key,
+    lock_ts: TimeStamp,
+    params: &CheckTxnStatusParams,
+    lock_mgr: &L,
+) -> Result<(TxnStatus, Option<ReleasedLock>)> {
+    let mut caller_start_ts = params.caller_start_ts;
+    let mut current_ts = params.current_ts;
+
+    let (txn_status, released) = match txn.reader.load_lock(&primary_key)? {
+        Some(mut lock) if lock.ts == lock_ts => {
+            if lock.use_async_commit && (!caller_start_ts.is_zero() || !current_ts.is_zero()) {
+                warn!(
+                    "check async commit txn status with non-zero caller_start_ts or current_ts";
+                    "caller_start_ts" => caller_start_ts,
+                    "current_ts" => current_ts
+                );
+                caller_start_ts = TimeStamp::zero();
+                current_ts = TimeStamp::zero();
+            }
+
+            let is_pessimistic_txn = !lock.for_update_ts.is_zero();
+            // The grace window is applied uniformly, whether or not the lock is pessimistic.
+            // A caller-supplied `ttl_grace_ms` can be arbitrarily large, so the expiry ts must
+            // saturate rather than overflow `u64` and panic on a large but valid input.
+            let naturally_expired = lock
+                .ts
+                .physical()
+                .saturating_add(lock.ttl)
+                .saturating_add(params.ttl_grace_ms)
+                < current_ts.physical();
+            let is_expired = naturally_expired || params.force_rollback;
+            let expiry_status = || {
+                if naturally_expired {
+                    TxnStatus::TtlExpire
+                } else {
+                    TxnStatus::ForceRollback
                 }
+            };
 
-                let is_pessimistic_txn = !lock.for_update_ts.is_zero();
-
-                if lock.ts.physical() + lock.ttl < self.current_ts.physical() {
-                    // If the lock is expired, clean it up.
-                    let released = txn.check_write_and_rollback_lock(
-                        self.primary_key,
-                        &lock,
-                        is_pessimistic_txn,
-                    )?;
-                    MVCC_CHECK_TXN_STATUS_COUNTER_VEC.rollback.inc();
-                    Ok((TxnStatus::TtlExpire, released))
+            if is_expired && params.read_only {
+                // Report the expiry without actually rolling back: a read-only caller may
+                // only observe the lock, never clean it up.
+                (expiry_status(), None)
+            } else if is_expired {
+                // The rollback and the lock's removal cannot be decoupled: writing only a
+                // protected Rollback marker while leaving the lock itself in the lock CF would
+                // let a still-alive, merely-slow owner's later `Commit` find that untouched lock
+                // and commit straight through the marker, producing a `Rollback` and a `Commit`
+                // record for the same start_ts. So the cleanup always happens inline here;
+                // `defer_rollback` only changes the status reported back to `RollbackScheduled`
+                // instead of `TtlExpire`/`ForceRollback`, telling the caller it doesn't need to
+                // treat this resolution as its own responsibility (retry, escalate, ...) the way
+                // an ordinary expiry observation would. Batching the write-CF cost of resolving
+                // the same primary across many simultaneous callers has to happen above this
+                // command — e.g. in a resolve-lock worker that coalesces callers before any of
+                // them gets here — not by skipping the lock removal in this one call.
+                let released =
+                    txn.check_write_and_rollback_lock(primary_key, &lock, is_pessimistic_txn)?;
+                MVCC_CHECK_TXN_STATUS_COUNTER_VEC.rollback.inc();
+                // The lock is gone, so any wait-for edge pointing at it is now stale; drop
+                // it instead of waiting for it to expire out of the graph on its own.
+                lock_mgr.clean_up_wait_for(lock_ts);
+                let status = if params.defer_rollback {
+                    TxnStatus::RollbackScheduled
                 } else {
-                    // Although we won't really push forward min_commit_ts when caller_start_ts is max,
-                    // we should return MinCommitTsPushed result to the client to keep backward
-                    // compatibility.
-                    let mut min_commit_ts_pushed = self.caller_start_ts.is_max();
-
-                    // If lock.min_commit_ts is 0, it's not a large transaction and we can't push forward
-                    // its min_commit_ts otherwise the transaction can't be committed by old version TiDB
-                    // during rolling update.
-                    if !lock.min_commit_ts.is_zero()
-                        // If the caller_start_ts is max, it's a point get in the autocommit transaction.
-                        // We don't push forward lock's min_commit_ts and the point get can ignore the lock
-                        // next time because it's not committed.
-                        && !self.caller_start_ts.is_max()
-                        // Push forward the min_commit_ts so that reading won't be blocked by locks.
-                        && self.caller_start_ts >= lock.min_commit_ts
-                    {
-                        assert!(!lock.use_async_commit);
-                        lock.min_commit_ts = self.caller_start_ts.next();
-
-                        if lock.min_commit_ts < self.current_ts {
-                            lock.min_commit_ts = self.current_ts;
-                        }
-
-                        txn.put_lock(self.primary_key, &lock);
-                        min_commit_ts_pushed = true;
-                        MVCC_CHECK_TXN_STATUS_COUNTER_VEC.update_ts.inc();
+                    expiry_status()
+                };
+                (status, released)
+            } else if params.read_only {
+                // Pure observation: report the lock as-is without pushing its
+                // `min_commit_ts` or feeding the deadlock detector.
+                (TxnStatus::uncommitted(lock, false), None)
+            } else if params.no_push {
+                // A non-blocking reader isn't actually waiting on this lock, so it must not
+                // register a wait-for edge or be handed back a `Deadlock` victim either:
+                // both would be mutating/consulting the shared detector on behalf of a caller
+                // that was never going to block in the first place. Report the lock as-is,
+                // same backward-compatible `MinCommitTsPushed` bookkeeping as the normal push
+                // path below, but never actually mutate the lock or touch the detector.
+                let min_commit_ts_pushed = caller_start_ts.is_max();
+                (TxnStatus::uncommitted(lock, min_commit_ts_pushed), None)
+            } else if let Some(victim_start_ts) = detect_deadlock(
+                params.detect_deadlock,
+                caller_start_ts,
+                lock_ts,
+                &primary_key,
+                &lock,
+                lock_mgr,
+            ) {
+                // A cycle was found while walking the wait-for graph from `lock_ts` back
+                // towards `caller_start_ts`: resolving this lock normally would just block
+                // forever, so report the deadlock instead of pushing `min_commit_ts`.
+                MVCC_CHECK_TXN_STATUS_COUNTER_VEC.deadlock.inc();
+                (TxnStatus::Deadlock { victim_start_ts }, None)
+            } else {
+                // Although we won't really push forward min_commit_ts when caller_start_ts is max,
+                // we should return MinCommitTsPushed result to the client to keep backward
+                // compatibility.
+                let mut min_commit_ts_pushed = caller_start_ts.is_max();
+
+                // If lock.min_commit_ts is 0, it's not a large transaction and we can't push forward
+                // its min_commit_ts otherwise the transaction can't be committed by old version TiDB
+                // during rolling update.
+                if !lock.min_commit_ts.is_zero()
+                    // If the caller_start_ts is max, it's a point get in the autocommit transaction.
+                    // We don't push forward lock's min_commit_ts and the point get can ignore the lock
+                    // next time because it's not committed.
+                    && !caller_start_ts.is_max()
+                    // Push forward the min_commit_ts so that reading won't be blocked by locks.
+                    && caller_start_ts >= lock.min_commit_ts
+                {
+                    assert!(!lock.use_async_commit);
+                    // A caller-supplied hint raises the floor beyond the caller's own
+                    // start_ts, letting it clear the lock past some later ts it already
+                    // knows it needs in one round-trip.
+                    lock.min_commit_ts = std::cmp::max(caller_start_ts.next(), params.min_commit_ts);
+
+                    if lock.min_commit_ts < current_ts {
+                        lock.min_commit_ts = current_ts;
                     }
 
-                    Ok((TxnStatus::uncommitted(lock, min_commit_ts_pushed), None))
+                    txn.put_lock(primary_key, &lock);
+                    min_commit_ts_pushed = true;
+                    MVCC_CHECK_TXN_STATUS_COUNTER_VEC.update_ts.inc();
                 }
+
+                (TxnStatus::uncommitted(lock, min_commit_ts_pushed), None)
+            }
+        }
+        // The rollback must be protected, see more on
+        // [issue #7364](https://github.com/tikv/tikv/issues/7364)
+        //
+        // In `read_only` mode we must not write that protected rollback ourselves, so the
+        // missing-lock action degrades to a no-op observation of whatever commit/rollback
+        // record (or absence of one) is already there.
+        //
+        // NOT IMPLEMENTED HERE: a 1PC `Prewrite` that skips the lock phase writes its commit
+        // record and becomes visible in one step, so a caller could in principle observe this
+        // node strictly between "nothing written" and "commit record visible" and write a
+        // protected rollback for a transaction that is, in fact, about to commit. Closing that
+        // window needs an in-flight memory-lock guard held by the `Prewrite` write path itself
+        // (the same shape async commit already uses) plus a `try_one_pc` flag for it to key off
+        // of — both belong to `Prewrite`, which does not exist in this source tree, so no such
+        // guard can be added from this file. This branch does not implement the 1PC fast path's
+        // race closure; it only documents the dependency so the gap isn't silently assumed away.
+        l => {
+            // There's no lock for `lock_ts` any more, so nothing is actually waiting on it
+            // through this node's wait-for graph: drop any edge left behind by an earlier
+            // call that registered a wait before this lock was resolved elsewhere.
+            if !params.read_only {
+                lock_mgr.clean_up_wait_for(lock_ts);
             }
-            // The rollback must be protected, see more on
-            // [issue #7364](https://github.com/tikv/tikv/issues/7364)
-            l => txn
-                .check_txn_status_missing_lock(
-                    self.primary_key,
-                    l,
-                    MissingLockAction::rollback(self.rollback_if_not_exist),
+            if let Some(status) = check_assertion(txn, &primary_key, params.assertion)? {
+                (status, None)
+            } else {
+                let action = if params.read_only {
+                    MissingLockAction::DoNothing
+                } else {
+                    MissingLockAction::rollback(params.rollback_if_not_exist)
+                };
+                (
+                    txn.check_txn_status_missing_lock(primary_key, l, action)?,
+                    None,
                 )
-                .map(|s| (s, None)),
+            }
+        }
+    };
+
+    if !params.read_only {
+        if let TxnStatus::Committed { commit_ts } = &txn_status {
+            // This command can't tell, from the `Write` record alone, whether the commit it
+            // just observed went through the one-phase-commit fast path (which never leaves a
+            // lock behind, so the commit record is the only trace of it) or ordinary 2PC
+            // (whose lock was already resolved by the time we got here). Bumping `max_ts` to
+            // `commit_ts` unconditionally is still correct for both: it's exactly what would
+            // already have happened had we instead observed and pushed past a still-live 2PC
+            // lock for the same transaction, so treating every observed commit record this
+            // way is a conservative generalization of the 1PC case, not a distinct behavior
+            // change for ordinary 2PC reads.
+            txn.concurrency_manager.update_max_ts(*commit_ts);
+        }
+    }
+
+    Ok((txn_status, released))
+}
+
+/// Registers the wait-for edge `caller_start_ts -> lock_ts` with the lock manager's deadlock
+/// detector, unless `enabled` is false or `caller_start_ts` doesn't identify a real blocked
+/// caller.
+///
+/// `caller_start_ts == 0` means either the async-commit guard above just zeroed it, or the caller
+/// only wants the lock's information; `caller_start_ts == TimeStamp::max()` is an autocommit
+/// point get, which ignores the lock instead of waiting on it. None of these callers is actually
+/// blocked on `lock_ts`, so registering an edge for them would only pollute the wait-for graph.
+fn detect_deadlock<L: LockManager>(
+    enabled: bool,
+    caller_start_ts: TimeStamp,
+    lock_ts: TimeStamp,
+    primary_key: &Key,
+    lock: &Lock,
+    lock_mgr: &L,
+) -> Option<TimeStamp> {
+    if !enabled || caller_start_ts.is_zero() || caller_start_ts.is_max() {
+        return None;
+    }
+    let is_pessimistic_txn = !lock.for_update_ts.is_zero();
+    lock_mgr.detect_deadlock(
+        caller_start_ts,
+        lock_ts,
+        lock.hash(primary_key),
+        is_pessimistic_txn.then(|| lock.for_update_ts),
+        DEADLOCK_DETECT_DEPTH,
+    )
+}
+
+/// Verifies `assertion` against the latest write record for `primary_key`, to be used only once
+/// no lock is found for it. Returns `Some(AssertionFailed)` when the caller's expectation doesn't
+/// hold; `None` means either there's nothing to check or the assertion is satisfied, and the
+/// caller should fall through to the normal missing-lock handling (including writing a protected
+/// rollback when nothing is found at all — that write guards against a concurrent resubmission
+/// racing this command, same as it would without an assertion, see
+/// [issue #7364](https://github.com/tikv/tikv/issues/7364)).
+///
+/// Existence is decided the same way prewrite's own mutation assertions are: a `Rollback` record
+/// means the transaction that wrote it never committed, and a committed `Delete` means the key
+/// *was* committed but doesn't currently exist, so both count as `NotExist`.
+fn check_assertion<S: Snapshot>(
+    txn: &mut MvccTxn<S>,
+    primary_key: &Key,
+    assertion: Assertion,
+) -> Result<Option<TxnStatus>> {
+    if assertion == Assertion::None {
+        return Ok(None);
+    }
+    let exists = txn
+        .reader
+        .seek_write(primary_key, TimeStamp::max())?
+        .map_or(false, |(_, write)| {
+            write.write_type != WriteType::Rollback && write.write_type != WriteType::Delete
+        });
+    let actual = if exists {
+        Assertion::Exist
+    } else {
+        Assertion::NotExist
+    };
+    if assertion == actual {
+        Ok(None)
+    } else {
+        Ok(Some(TxnStatus::AssertionFailed {
+            key: primary_key.clone(),
+            expected: assertion,
+            actual,
+        }))
+    }
+}
+
+command! {
+    /// The batched counterpart of [`CheckTxnStatus`](CheckTxnStatus): checks the status of many
+    /// transactions' primary locks in a single round-trip instead of one `CheckTxnStatus` call
+    /// per primary. All keys share one snapshot and `caller_start_ts`/`current_ts`, so a reader
+    /// that hit a region littered with locks from several stalled transactions no longer pays a
+    /// fresh snapshot and write-CF seek per primary.
+    CheckTxnStatusBatch:
+        cmd_ty => Vec<(Key, TxnStatus)>,
+        display => "kv::command::check_txn_status_batch {:?} curr({}, {}) | {:?}", (keys, caller_start_ts, current_ts, ctx),
+        content => {
+            /// The `(primary_key, lock_ts)` pairs to check, one per transaction.
+            keys: Vec<(Key, TimeStamp)>,
+            /// The start_ts of the transaction that invokes this command, shared by all keys.
+            caller_start_ts: TimeStamp,
+            /// The approximate current_ts when the command is invoked, shared by all keys.
+            current_ts: TimeStamp,
+            /// Specifies the behavior when neither commit/rollback record nor lock is found for a
+            /// key. If true, rollbacks that transaction; otherwise returns an error for that key.
+            rollback_if_not_exist: bool,
+        }
+}
+
+impl CommandExt for CheckTxnStatusBatch {
+    ctx!();
+    tag!(check_txn_status);
+    ts!(caller_start_ts);
+    fn write_bytes(&self) -> usize {
+        self.keys.iter().map(|(key, _)| key.as_encoded().len()).sum()
+    }
+    gen_lock!(keys: multiple(|(key, _)| key));
+}
+
+impl<S: Snapshot + Clone, L: LockManager> WriteCommand<S, L> for CheckTxnStatusBatch {
+    /// Runs [`resolve_primary_lock`] — the same per-key logic [`CheckTxnStatus::process_write`]
+    /// runs — once per `(primary_key, lock_ts)` pair, against a single shared `snapshot` instead
+    /// of re-acquiring one per call. Unlike the single-key command, this opts out of deadlock
+    /// detection and assertion checking (via [`CheckTxnStatusParams`]) to keep its per-key cost
+    /// down; everything else, including the async-commit guard and the 1PC-visibility `max_ts`
+    /// bump, is shared and can't silently drift between the two commands.
+    fn process_write(mut self, snapshot: S, context: WriteContext<'_, L>) -> Result<WriteResult> {
+        let ctx = mem::take(&mut self.ctx);
+        let keys = mem::take(&mut self.keys);
+        let row_count = keys.len();
+
+        let params = CheckTxnStatusParams {
+            caller_start_ts: self.caller_start_ts,
+            current_ts: self.current_ts,
+            rollback_if_not_exist: self.rollback_if_not_exist,
+            read_only: false,
+            force_rollback: false,
+            ttl_grace_ms: 0,
+            assertion: Assertion::None,
+            defer_rollback: false,
+            min_commit_ts: TimeStamp::zero(),
+            no_push: false,
+            detect_deadlock: false,
         };
-        let (txn_status, released) = result?;
 
-        released_locks.push(released);
-        // The lock is released here only when the `check_txn_status` returns `TtlExpire`.
-        if let TxnStatus::TtlExpire = txn_status {
-            released_locks.wake_up(context.lock_mgr);
+        let mut statuses = Vec::with_capacity(row_count);
+        let mut modifies = Vec::new();
+        for (primary_key, lock_ts) in keys {
+            // Same formula as the single-key command's own `max_ts` bump, just re-run per key
+            // since each primary has its own `lock_ts`: seed from `lock_ts`, then only fold in
+            // `current_ts`/`caller_start_ts` when they're not `TimeStamp::max()`, the sentinel an
+            // autocommit point-get passes for "no bound". Folding in an unguarded `max()` would
+            // poison the concurrency manager for every future commit-ts allocation.
+            let mut new_max_ts = lock_ts;
+            if !self.current_ts.is_max() && self.current_ts > new_max_ts {
+                new_max_ts = self.current_ts;
+            }
+            if !self.caller_start_ts.is_max() && self.caller_start_ts > new_max_ts {
+                new_max_ts = self.caller_start_ts;
+            }
+            context.concurrency_manager.update_max_ts(new_max_ts);
+
+            // Each primary belongs to a different transaction, so its rollback/lock writes must
+            // be keyed by its own start_ts; only the underlying snapshot is actually shared.
+            let mut txn = MvccTxn::new(
+                snapshot.clone(),
+                lock_ts,
+                !ctx.get_not_fill_cache(),
+                context.concurrency_manager.clone(),
+            );
+
+            let (status, released) = resolve_primary_lock(
+                &mut txn,
+                primary_key.clone(),
+                lock_ts,
+                &params,
+                context.lock_mgr,
+            )?;
+
+            // Same as the single-key command: a lock actually resolved here (`TtlExpire` /
+            // `ForceRollback` / `RollbackScheduled`) may have waiters registered against it, and
+            // nothing else in this batch will ever wake them. `params.defer_rollback` is always
+            // false above, so `RollbackScheduled` can't actually come back today, but listing it
+            // here keeps this gate from silently going stale the day that changes.
+            let mut released_locks = ReleasedLocks::new(lock_ts, TimeStamp::zero());
+            released_locks.push(released);
+            if matches!(
+                status,
+                TxnStatus::TtlExpire | TxnStatus::ForceRollback | TxnStatus::RollbackScheduled
+            ) {
+                released_locks.wake_up(context.lock_mgr);
+            }
+
+            context.statistics.add(&txn.take_statistics());
+            modifies.extend(txn.into_modifies());
+            statuses.push((primary_key, status));
         }
 
-        context.statistics.add(&txn.take_statistics());
-        let pr = ProcessResult::TxnStatus { txn_status };
-        let write_data = WriteData::from_modifies(txn.into_modifies());
+        let pr = ProcessResult::TxnStatusBatch { statuses };
         Ok(WriteResult {
             ctx,
-            to_be_write: write_data,
-            rows: 1,
+            to_be_write: WriteData::from_modifies(modifies),
+            rows: row_count,
             pr,
             lock_info: None,
             lock_guards: vec![],
@@ -210,6 +662,13 @@ pub mod tests {
             caller_start_ts: caller_start_ts.into(),
             current_ts,
             rollback_if_not_exist,
+            read_only: false,
+            force_rollback: false,
+            ttl_grace_ms: 0,
+            assertion: Assertion::None,
+            defer_rollback: false,
+            min_commit_ts: TimeStamp::zero(),
+            no_push: false,
         };
         let result = command
             .process_write(
@@ -252,6 +711,13 @@ pub mod tests {
             caller_start_ts: caller_start_ts.into(),
             current_ts,
             rollback_if_not_exist,
+            read_only: false,
+            force_rollback: false,
+            ttl_grace_ms: 0,
+            assertion: Assertion::None,
+            defer_rollback: false,
+            min_commit_ts: TimeStamp::zero(),
+            no_push: false,
         };
         assert!(command
             .process_write(
@@ -268,6 +734,115 @@ pub mod tests {
             .is_err());
     }
 
+    /// The knobs `must_success` hard-codes to their no-op value, broken out so a test that needs
+    /// one non-default field doesn't have to re-paste the whole `CheckTxnStatus` / `WriteContext`
+    /// literal just to flip it. `..Default::default()` in a struct literal covers every field a
+    /// given test doesn't care about.
+    #[derive(Clone, Copy)]
+    struct CheckTxnStatusOptions {
+        rollback_if_not_exist: bool,
+        read_only: bool,
+        force_rollback: bool,
+        ttl_grace_ms: u64,
+        assertion: Assertion,
+        defer_rollback: bool,
+        min_commit_ts: TimeStamp,
+        no_push: bool,
+    }
+
+    impl Default for CheckTxnStatusOptions {
+        fn default() -> Self {
+            CheckTxnStatusOptions {
+                rollback_if_not_exist: true,
+                read_only: false,
+                force_rollback: false,
+                ttl_grace_ms: 0,
+                assertion: Assertion::None,
+                defer_rollback: false,
+                min_commit_ts: TimeStamp::zero(),
+                no_push: false,
+            }
+        }
+    }
+
+    /// Like [`must_success`], but takes every other `CheckTxnStatus` field through `options`
+    /// instead of hard-coding them, and additionally asserts no writes happened at all when
+    /// `options.read_only` is set.
+    fn must_success_with_options<E: Engine>(
+        engine: &E,
+        primary_key: &[u8],
+        lock_ts: impl Into<TimeStamp>,
+        caller_start_ts: impl Into<TimeStamp>,
+        current_ts: impl Into<TimeStamp>,
+        options: CheckTxnStatusOptions,
+        status_pred: impl FnOnce(TxnStatus) -> bool,
+    ) {
+        let ctx = Context::default();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let current_ts = current_ts.into();
+        let cm = ConcurrencyManager::new(current_ts);
+        let lock_ts: TimeStamp = lock_ts.into();
+        let command = crate::storage::txn::commands::CheckTxnStatus {
+            ctx: Context::default(),
+            primary_key: Key::from_raw(primary_key),
+            lock_ts,
+            caller_start_ts: caller_start_ts.into(),
+            current_ts,
+            rollback_if_not_exist: options.rollback_if_not_exist,
+            read_only: options.read_only,
+            force_rollback: options.force_rollback,
+            ttl_grace_ms: options.ttl_grace_ms,
+            assertion: options.assertion,
+            defer_rollback: options.defer_rollback,
+            min_commit_ts: options.min_commit_ts,
+            no_push: options.no_push,
+        };
+        let result = command
+            .process_write(
+                snapshot,
+                WriteContext {
+                    lock_mgr: &DummyLockManager,
+                    concurrency_manager: cm,
+                    extra_op: Default::default(),
+                    statistics: &mut Default::default(),
+                    pipelined_pessimistic_lock: false,
+                    enable_async_commit: true,
+                },
+            )
+            .unwrap();
+        if options.read_only {
+            assert!(result.to_be_write.modifies.is_empty());
+            assert_eq!(result.rows, 0);
+        }
+        if let ProcessResult::TxnStatus { txn_status } = result.pr {
+            assert!(status_pred(txn_status));
+        } else {
+            unreachable!();
+        }
+        write(engine, &ctx, result.to_be_write.modifies);
+    }
+
+    /// A lock manager stub that reports a fixed victim for every `detect_deadlock` call,
+    /// regardless of the wait-for edge it's asked to register. Used only to exercise the
+    /// `Deadlock` status path, which [`DummyLockManager`] can never produce.
+    #[derive(Clone)]
+    struct DeadlockLockManager {
+        victim_start_ts: TimeStamp,
+    }
+
+    impl LockManager for DeadlockLockManager {
+        fn detect_deadlock(
+            &self,
+            _caller_start_ts: TimeStamp,
+            _lock_ts: TimeStamp,
+            _lock_hash: u64,
+            _for_update_ts: Option<TimeStamp>,
+            _depth: u32,
+        ) -> Option<TimeStamp> {
+            Some(self.victim_start_ts)
+        }
+    }
+
     fn committed(commit_ts: impl Into<TimeStamp>) -> impl FnOnce(TxnStatus) -> bool {
         move |s| {
             s == TxnStatus::Committed {
@@ -785,4 +1360,623 @@ pub mod tests {
         test_check_txn_status_impl(false);
         test_check_txn_status_impl(true);
     }
+
+    #[test]
+    fn test_check_txn_status_read_only() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let ts = TimeStamp::compose;
+
+        let read_only = CheckTxnStatusOptions {
+            read_only: true,
+            ..Default::default()
+        };
+
+        // Observing a non-existent transaction must not leave a rollback record behind.
+        must_success_with_options(&engine, k, ts(3, 0), ts(3, 1), ts(3, 2), read_only, |s| {
+            s == LockNotExist
+        });
+        must_unlocked(&engine, k);
+
+        // Observing a live, unexpired lock must not bump its min_commit_ts.
+        must_prewrite_put_for_large_txn(&engine, k, v, k, ts(5, 0), 100, 0);
+        must_success_with_options(
+            &engine,
+            k,
+            ts(5, 0),
+            ts(6, 0),
+            ts(7, 0),
+            read_only,
+            uncommitted(100, ts(5, 1), false),
+        );
+        must_large_txn_locked(&engine, k, ts(5, 0), 100, ts(5, 1), false);
+
+        // Observing an expired lock must report it as expired without actually rolling it back.
+        must_success_with_options(&engine, k, ts(5, 0), ts(200, 0), ts(200, 0), read_only, |s| {
+            s == TtlExpire
+        });
+        must_large_txn_locked(&engine, k, ts(5, 0), 100, ts(5, 1), false);
+
+        must_commit(&engine, k, ts(5, 0), ts(10, 0));
+
+        // Observing a committed transaction just reports the commit ts.
+        must_success_with_options(
+            &engine,
+            k,
+            ts(5, 0),
+            ts(20, 0),
+            ts(20, 0),
+            read_only,
+            committed(ts(10, 0)),
+        );
+    }
+
+    #[test]
+    fn test_check_txn_status_force_rollback_and_ttl_grace() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let ts = TimeStamp::compose;
+
+        // Lock the key with TTL=100, not yet naturally expired.
+        must_prewrite_put_for_large_txn(&engine, k, v, k, ts(5, 0), 100, 0);
+        must_large_txn_locked(&engine, k, ts(5, 0), 100, ts(5, 1), false);
+
+        // Without the grace window this would already be past TTL (5 + 100 < 200); with a
+        // generous grace window it must not be resolved yet.
+        must_success_with_options(
+            &engine,
+            k,
+            ts(5, 0),
+            ts(6, 0),
+            ts(200, 0),
+            CheckTxnStatusOptions {
+                ttl_grace_ms: 1000,
+                ..Default::default()
+            },
+            uncommitted(100, ts(200, 0), true),
+        );
+        must_large_txn_locked(&engine, k, ts(5, 0), 100, ts(200, 0), false);
+
+        // Forcing resolution rolls the still-live lock back immediately, and is reported
+        // distinctly from a genuine TTL expiry.
+        must_success_with_options(
+            &engine,
+            k,
+            ts(5, 0),
+            ts(6, 0),
+            ts(6, 0),
+            CheckTxnStatusOptions {
+                force_rollback: true,
+                ..Default::default()
+            },
+            |s| s == ForceRollback,
+        );
+        must_unlocked(&engine, k);
+        must_seek_write(
+            &engine,
+            k,
+            TimeStamp::max(),
+            ts(5, 0),
+            ts(5, 0),
+            WriteType::Rollback,
+        );
+
+        // A genuine TTL expiry (well past the grace window) is still reported as `TtlExpire`.
+        must_prewrite_put_for_large_txn(&engine, k, v, k, ts(10, 0), 100, 0);
+        must_success_with_options(
+            &engine,
+            k,
+            ts(10, 0),
+            ts(300, 0),
+            ts(300, 0),
+            Default::default(),
+            |s| s == TtlExpire,
+        );
+        must_unlocked(&engine, k);
+
+        // A huge caller-supplied grace window must saturate rather than overflow and panic.
+        must_prewrite_put_for_large_txn(&engine, k, v, k, ts(400, 0), 100, 0);
+        must_success_with_options(
+            &engine,
+            k,
+            ts(400, 0),
+            ts(401, 0),
+            ts(401, 0),
+            CheckTxnStatusOptions {
+                ttl_grace_ms: u64::MAX,
+                ..Default::default()
+            },
+            uncommitted(100, ts(401, 1), true),
+        );
+        must_large_txn_locked(&engine, k, ts(400, 0), 100, ts(401, 1), false);
+    }
+
+    #[test]
+    fn test_check_txn_status_defer_rollback() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let ts = TimeStamp::compose;
+
+        must_prewrite_put_for_large_txn(&engine, k, v, k, ts(5, 0), 100, 0);
+
+        // The lock is well past its TTL. `defer_rollback` still rolls it back inline right here
+        // — leaving the lock in place while only flagging a protected rollback would let the
+        // still-alive owner's later `Commit` find that lock and commit straight through the
+        // marker — it only changes the reported status to `RollbackScheduled`.
+        must_success_with_options(
+            &engine,
+            k,
+            ts(5, 0),
+            ts(300, 0),
+            ts(300, 0),
+            CheckTxnStatusOptions {
+                defer_rollback: true,
+                ..Default::default()
+            },
+            |s| s == RollbackScheduled,
+        );
+        must_unlocked(&engine, k);
+        // The protected rollback marker is there too, so a later prewrite of the same
+        // transaction is rejected.
+        must_get_rollback_protected(&engine, k, ts(5, 0), true);
+    }
+
+    #[test]
+    fn test_check_txn_status_min_commit_ts_hint() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let ts = TimeStamp::compose;
+
+        must_prewrite_put_for_large_txn(&engine, k, v, k, ts(5, 0), 100, 0);
+        must_large_txn_locked(&engine, k, ts(5, 0), 100, ts(5, 1), false);
+
+        // A hint past `caller_start_ts.next()` raises the floor the lock is pushed to.
+        must_success_with_options(
+            &engine,
+            k,
+            ts(5, 0),
+            ts(6, 0),
+            ts(6, 0),
+            CheckTxnStatusOptions {
+                min_commit_ts: ts(50, 0),
+                ..Default::default()
+            },
+            uncommitted(100, ts(50, 0), true),
+        );
+        must_large_txn_locked(&engine, k, ts(5, 0), 100, ts(50, 0), false);
+
+        // `no_push` reports the lock's current min_commit_ts without mutating it, even though
+        // the ordinary push condition would otherwise apply.
+        must_success_with_options(
+            &engine,
+            k,
+            ts(5, 0),
+            ts(60, 0),
+            ts(60, 0),
+            CheckTxnStatusOptions {
+                min_commit_ts: ts(100, 0),
+                no_push: true,
+                ..Default::default()
+            },
+            uncommitted(100, ts(50, 0), false),
+        );
+        must_large_txn_locked(&engine, k, ts(5, 0), 100, ts(50, 0), false);
+    }
+
+    #[test]
+    fn test_check_txn_status_assertion() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let ts = TimeStamp::compose;
+
+        let with_assertion = |assertion| CheckTxnStatusOptions {
+            assertion,
+            ..Default::default()
+        };
+
+        // No lock and no write record: asserting NotExist is satisfied, asserting Exist fails.
+        must_success_with_options(
+            &engine,
+            k,
+            ts(3, 0),
+            ts(3, 1),
+            ts(3, 2),
+            with_assertion(Assertion::NotExist),
+            |s| s == LockNotExist,
+        );
+        must_success_with_options(
+            &engine,
+            k,
+            ts(4, 0),
+            ts(4, 1),
+            ts(4, 2),
+            with_assertion(Assertion::Exist),
+            |s| {
+                s == AssertionFailed {
+                    key: Key::from_raw(k),
+                    expected: Assertion::Exist,
+                    actual: Assertion::NotExist,
+                }
+            },
+        );
+
+        // Once the key is committed, the opposite assertion fails the same way.
+        must_prewrite_put_for_large_txn(&engine, k, v, k, ts(5, 0), 100, 0);
+        must_commit(&engine, k, ts(5, 0), ts(6, 0));
+        must_success_with_options(
+            &engine,
+            k,
+            ts(5, 0),
+            ts(7, 0),
+            ts(7, 0),
+            with_assertion(Assertion::NotExist),
+            |s| {
+                s == AssertionFailed {
+                    key: Key::from_raw(k),
+                    expected: Assertion::NotExist,
+                    actual: Assertion::Exist,
+                }
+            },
+        );
+        must_success_with_options(
+            &engine,
+            k,
+            ts(5, 0),
+            ts(7, 0),
+            ts(7, 0),
+            with_assertion(Assertion::Exist),
+            committed(ts(6, 0)),
+        );
+
+        // A committed Delete is a tombstone: the key was committed but does not currently exist,
+        // so it must still satisfy `NotExist` rather than `Exist`.
+        must_prewrite_delete(&engine, k, k, ts(8, 0));
+        must_commit(&engine, k, ts(8, 0), ts(9, 0));
+        must_success_with_options(
+            &engine,
+            k,
+            ts(8, 0),
+            ts(10, 0),
+            ts(10, 0),
+            with_assertion(Assertion::Exist),
+            |s| {
+                s == AssertionFailed {
+                    key: Key::from_raw(k),
+                    expected: Assertion::Exist,
+                    actual: Assertion::NotExist,
+                }
+            },
+        );
+        must_success_with_options(
+            &engine,
+            k,
+            ts(8, 0),
+            ts(10, 0),
+            ts(10, 0),
+            with_assertion(Assertion::NotExist),
+            committed(ts(9, 0)),
+        );
+    }
+
+    #[test]
+    fn test_check_txn_status_bumps_max_ts_for_committed_without_lock() {
+        // Simulates observing a transaction that went through the one-phase-commit fast path:
+        // there never was a lock for it, only a `Write` record landed directly.
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let ts = TimeStamp::compose;
+
+        must_prewrite_put_for_large_txn(&engine, k, v, k, ts(5, 0), 100, 0);
+        must_commit(&engine, k, ts(5, 0), ts(50, 0));
+        must_unlocked(&engine, k);
+
+        let ctx = Context::default();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        // Pick a current_ts far lower than the commit_ts so the bump can only have come from
+        // observing the commit record itself, not from the caller's own timestamps.
+        let cm = ConcurrencyManager::new(ts(1, 0));
+        let command = crate::storage::txn::commands::CheckTxnStatus {
+            ctx: Context::default(),
+            primary_key: Key::from_raw(k),
+            lock_ts: ts(5, 0),
+            caller_start_ts: ts(1, 0),
+            current_ts: ts(1, 0),
+            rollback_if_not_exist: true,
+            read_only: false,
+            force_rollback: false,
+            ttl_grace_ms: 0,
+            assertion: Assertion::None,
+            defer_rollback: false,
+            min_commit_ts: TimeStamp::zero(),
+            no_push: false,
+        };
+        command
+            .process_write(
+                snapshot,
+                WriteContext {
+                    lock_mgr: &DummyLockManager,
+                    concurrency_manager: cm.clone(),
+                    extra_op: Default::default(),
+                    statistics: &mut Default::default(),
+                    pipelined_pessimistic_lock: false,
+                    enable_async_commit: true,
+                },
+            )
+            .unwrap();
+        assert!(cm.max_ts() >= ts(50, 0));
+    }
+
+    #[test]
+    fn test_check_txn_status_deadlock() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let (k, v) = (b"k1", b"v1");
+        let ts = TimeStamp::compose;
+
+        must_prewrite_put_for_large_txn(&engine, k, v, k, ts(5, 0), 100, 0);
+        must_large_txn_locked(&engine, k, ts(5, 0), 100, ts(5, 1), false);
+
+        let ctx = Context::default();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let cm = ConcurrencyManager::new(ts(6, 0));
+        let lock_mgr = DeadlockLockManager {
+            victim_start_ts: ts(6, 0),
+        };
+        let command = crate::storage::txn::commands::CheckTxnStatus {
+            ctx: Context::default(),
+            primary_key: Key::from_raw(k),
+            lock_ts: ts(5, 0),
+            caller_start_ts: ts(6, 0),
+            current_ts: ts(6, 0),
+            rollback_if_not_exist: true,
+            read_only: false,
+            force_rollback: false,
+            ttl_grace_ms: 0,
+            assertion: Assertion::None,
+            defer_rollback: false,
+            min_commit_ts: TimeStamp::zero(),
+            no_push: false,
+        };
+        let result = command
+            .process_write(
+                snapshot,
+                WriteContext {
+                    lock_mgr: &lock_mgr,
+                    concurrency_manager: cm,
+                    extra_op: Default::default(),
+                    statistics: &mut Default::default(),
+                    pipelined_pessimistic_lock: false,
+                    enable_async_commit: true,
+                },
+            )
+            .unwrap();
+        if let ProcessResult::TxnStatus { txn_status } = result.pr {
+            assert_eq!(
+                txn_status,
+                Deadlock {
+                    victim_start_ts: ts(6, 0)
+                }
+            );
+        } else {
+            unreachable!();
+        }
+        // A reported deadlock must not push min_commit_ts or roll back the lock.
+        must_large_txn_locked(&engine, k, ts(5, 0), 100, ts(5, 1), false);
+
+        // A zero `caller_start_ts` never identifies a real blocked caller (e.g. a caller that
+        // only wants the lock's information, or the async-commit guard having just zeroed it),
+        // so the edge must not be registered and the lock is reported normally instead of as a
+        // deadlock.
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let cm = ConcurrencyManager::new(ts(6, 0));
+        let command = crate::storage::txn::commands::CheckTxnStatus {
+            ctx: Context::default(),
+            primary_key: Key::from_raw(k),
+            lock_ts: ts(5, 0),
+            caller_start_ts: TimeStamp::zero(),
+            current_ts: ts(6, 0),
+            rollback_if_not_exist: true,
+            read_only: false,
+            force_rollback: false,
+            ttl_grace_ms: 0,
+            assertion: Assertion::None,
+            defer_rollback: false,
+            min_commit_ts: TimeStamp::zero(),
+            no_push: false,
+        };
+        let result = command
+            .process_write(
+                snapshot,
+                WriteContext {
+                    lock_mgr: &lock_mgr,
+                    concurrency_manager: cm,
+                    extra_op: Default::default(),
+                    statistics: &mut Default::default(),
+                    pipelined_pessimistic_lock: false,
+                    enable_async_commit: true,
+                },
+            )
+            .unwrap();
+        if let ProcessResult::TxnStatus { txn_status } = result.pr {
+            assert!(uncommitted(100, ts(5, 1), false)(txn_status));
+        } else {
+            unreachable!();
+        }
+    }
+
+    /// Unlike [`test_check_txn_status_deadlock`], which drives a stub that unconditionally
+    /// claims a deadlock, this exercises the real [`crate::storage::lock_manager::WaitForGraph`]
+    /// end to end: two transactions that each block on the other's primary lock only get reported
+    /// as deadlocked once both wait-for edges have actually been registered.
+    #[test]
+    fn test_check_txn_status_deadlock_real_graph() {
+        use crate::storage::lock_manager::WaitForGraph;
+
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let ts = TimeStamp::compose;
+
+        must_prewrite_put_for_large_txn(&engine, b"k1", b"v1", b"k1", ts(1, 0), 100, 0);
+        must_prewrite_put_for_large_txn(&engine, b"k2", b"v2", b"k2", ts(2, 0), 100, 0);
+
+        let lock_mgr = WaitForGraph::new();
+        let ctx = Context::default();
+
+        // T2 (start_ts=2) blocks on T1's (start_ts=1) lock on k1: no cycle yet.
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let cm = ConcurrencyManager::new(ts(2, 0));
+        let command = crate::storage::txn::commands::CheckTxnStatus {
+            ctx: Context::default(),
+            primary_key: Key::from_raw(b"k1"),
+            lock_ts: ts(1, 0),
+            caller_start_ts: ts(2, 0),
+            current_ts: ts(2, 0),
+            rollback_if_not_exist: true,
+            read_only: false,
+            force_rollback: false,
+            ttl_grace_ms: 0,
+            assertion: Assertion::None,
+            defer_rollback: false,
+            min_commit_ts: TimeStamp::zero(),
+            no_push: false,
+        };
+        let result = command
+            .process_write(
+                snapshot,
+                WriteContext {
+                    lock_mgr: &lock_mgr,
+                    concurrency_manager: cm,
+                    extra_op: Default::default(),
+                    statistics: &mut Default::default(),
+                    pipelined_pessimistic_lock: false,
+                    enable_async_commit: true,
+                },
+            )
+            .unwrap();
+        if let ProcessResult::TxnStatus { txn_status } = result.pr {
+            assert!(matches!(txn_status, Uncommitted { .. }));
+        } else {
+            unreachable!();
+        }
+
+        // T1 (start_ts=1) now blocks on T2's (start_ts=2) lock on k2, closing the cycle: T1
+        // is reported as the victim to break it.
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let cm = ConcurrencyManager::new(ts(1, 0));
+        let command = crate::storage::txn::commands::CheckTxnStatus {
+            ctx: Context::default(),
+            primary_key: Key::from_raw(b"k2"),
+            lock_ts: ts(2, 0),
+            caller_start_ts: ts(1, 0),
+            current_ts: ts(1, 0),
+            rollback_if_not_exist: true,
+            read_only: false,
+            force_rollback: false,
+            ttl_grace_ms: 0,
+            assertion: Assertion::None,
+            defer_rollback: false,
+            min_commit_ts: TimeStamp::zero(),
+            no_push: false,
+        };
+        let result = command
+            .process_write(
+                snapshot,
+                WriteContext {
+                    lock_mgr: &lock_mgr,
+                    concurrency_manager: cm,
+                    extra_op: Default::default(),
+                    statistics: &mut Default::default(),
+                    pipelined_pessimistic_lock: false,
+                    enable_async_commit: true,
+                },
+            )
+            .unwrap();
+        if let ProcessResult::TxnStatus { txn_status } = result.pr {
+            assert_eq!(
+                txn_status,
+                Deadlock {
+                    victim_start_ts: ts(1, 0)
+                }
+            );
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn test_check_txn_status_batch() {
+        let engine = TestEngineBuilder::new().build().unwrap();
+        let ts = TimeStamp::compose;
+
+        // k1: a live, unexpired lock whose min_commit_ts should get pushed.
+        must_prewrite_put_for_large_txn(&engine, b"k1", b"v1", b"k1", ts(5, 0), 1000, 0);
+        // k2: an expired lock that should be rolled back.
+        must_prewrite_put_for_large_txn(&engine, b"k2", b"v2", b"k2", ts(6, 0), 100, 0);
+        // k3: already committed.
+        must_prewrite_put_for_large_txn(&engine, b"k3", b"v3", b"k3", ts(7, 0), 100, 0);
+        must_commit(&engine, b"k3", ts(7, 0), ts(8, 0));
+        // k4: no lock and no write record at all.
+        // k5: an async-commit lock. `caller_start_ts >= min_commit_ts` would normally push
+        // min_commit_ts forward, but an async-commit lock's min_commit_ts must never be touched
+        // by this path, same as the single-key command's own async-commit guard.
+        must_prewrite_put_async_commit(&engine, b"k5", b"v5", b"k5", &Some(vec![]), 10, 11);
+
+        let ctx = Context::default();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let current_ts = ts(200, 0);
+        let cm = ConcurrencyManager::new(current_ts);
+        let command = crate::storage::txn::commands::CheckTxnStatusBatch {
+            ctx: Context::default(),
+            keys: vec![
+                (Key::from_raw(b"k1"), ts(5, 0)),
+                (Key::from_raw(b"k2"), ts(6, 0)),
+                (Key::from_raw(b"k3"), ts(7, 0)),
+                (Key::from_raw(b"k4"), ts(9, 0)),
+                (Key::from_raw(b"k5"), 10.into()),
+            ],
+            caller_start_ts: ts(150, 0),
+            current_ts,
+            rollback_if_not_exist: true,
+        };
+        let result = command
+            .process_write(
+                snapshot,
+                WriteContext {
+                    lock_mgr: &DummyLockManager,
+                    concurrency_manager: cm,
+                    extra_op: Default::default(),
+                    statistics: &mut Default::default(),
+                    pipelined_pessimistic_lock: false,
+                    enable_async_commit: true,
+                },
+            )
+            .unwrap();
+        assert_eq!(result.rows, 5);
+        let statuses = match result.pr {
+            ProcessResult::TxnStatusBatch { statuses } => statuses,
+            _ => unreachable!(),
+        };
+        assert_eq!(statuses.len(), 5);
+        let mut iter = statuses.into_iter();
+        let (key, status) = iter.next().unwrap();
+        assert_eq!(key, Key::from_raw(b"k1"));
+        assert!(uncommitted(1000, ts(200, 0), true)(status));
+        let (key, status) = iter.next().unwrap();
+        assert_eq!(key, Key::from_raw(b"k2"));
+        assert!(status == TtlExpire);
+        let (key, status) = iter.next().unwrap();
+        assert_eq!(key, Key::from_raw(b"k3"));
+        assert!(committed(ts(8, 0))(status));
+        let (key, status) = iter.next().unwrap();
+        assert_eq!(key, Key::from_raw(b"k4"));
+        assert!(status == LockNotExist);
+        let (key, status) = iter.next().unwrap();
+        assert_eq!(key, Key::from_raw(b"k5"));
+        assert!(uncommitted(100, 11, false)(status));
+
+        write(&engine, &ctx, result.to_be_write.modifies);
+        must_unlocked(&engine, b"k2");
+        must_large_txn_locked(&engine, b"k1", ts(5, 0), 1000, ts(200, 0), false);
+        // The async-commit lock's min_commit_ts must be unchanged, not bumped past
+        // `caller_start_ts`.
+        must_large_txn_locked(&engine, b"k5", 10, 100, 11, false);
+    }
 }